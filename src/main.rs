@@ -1,16 +1,78 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
-use std::fs::{create_dir, File, remove_dir_all, remove_file};
-use std::path::{Component, Path};
+use std::fs::{create_dir_all, File, remove_dir_all, remove_file};
+use std::path::{Component, Path, PathBuf};
 
 use askama::Template;
+use base64::Engine;
+use blake2::{Blake2b512, Digest};
 use chrono::{DateTime, Utc};
+use clap::Parser;
 use include_dir::{Dir, include_dir};
+use rss::{ChannelBuilder, Guid, ItemBuilder};
 use serde::Serialize;
+use sha2::Sha384;
 use size::Size;
 
 static VENDOR_DIR_NAME: &str = "_vendor";
 static VENDOR_DIR: Dir = include_dir!("vendor");
+static MANIFEST_FILE_NAME: &str = ".cdnizer-cache";
+static FEED_ITEM_LIMIT: usize = 20;
+static THUMBNAIL_CACHE_DIR_NAME: &str = ".cdnizer-thumbnails";
+static THUMBNAIL_SIZE: u32 = 128;
+
+/// Instant client-side filter over `search.json`, bundled into the
+/// vendor dir so `index.html` can wire up a search box without a server.
+static SEARCH_JS: &str = r#"
+(function () {
+    async function loadSearchIndex() {
+        const response = await fetch("/search.json");
+        return (await response.json()).entries;
+    }
+
+    function filterEntries(entries, query) {
+        const needle = query.trim().toLowerCase();
+        if (needle === "") return [];
+        return entries.filter((entry) => entry.name.toLowerCase().includes(needle));
+    }
+
+    window.cdnizerSearch = { loadSearchIndex, filterEntries };
+})();
+"#;
+
+/// Generates browsable `index.html`/`index.json` files for a CDN-style
+/// static file tree.
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Args {
+    /// Root directory to walk and index
+    #[arg(default_value = ".")]
+    root: String,
+
+    /// Name of the vendor asset directory extracted once at the root of
+    /// the output tree and referenced from every index
+    #[arg(long, default_value = VENDOR_DIR_NAME)]
+    vendor_dir: String,
+
+    /// Write generated indices and vendor assets here instead of mutating
+    /// `root` in place
+    #[arg(long)]
+    output_dir: Option<String>,
+
+    /// Compute Subresource Integrity hashes for files in index.json
+    #[arg(long)]
+    integrity: bool,
+}
+
+/// Settings that stay constant across the whole recursive walk, as
+/// opposed to the input/output directory pair which changes at each
+/// level of recursion.
+struct Config {
+    vendor_dir_name: String,
+    compute_integrity: bool,
+    thumbnail_cache_dir: PathBuf,
+}
 
 #[derive(Debug)]
 struct Breadcrumb {
@@ -25,8 +87,12 @@ struct Entry {
     path: String,
     #[serde(skip)]
     icon: String,
+    #[serde(skip)]
+    thumbnail: Option<String>,
     date: DateTime<Utc>,
     size: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    integrity: Option<String>,
 }
 
 #[derive(Debug, Template)]
@@ -35,6 +101,7 @@ struct IndexHtml {
     vendor_dir: String,
     breadcrumbs: Vec<Breadcrumb>,
     entries: Vec<Entry>,
+    readme_html: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -42,19 +109,127 @@ struct IndexJson {
     entries: Vec<Entry>,
 }
 
+/// One row of the whole-tree `search.json` used by the client-side
+/// instant search box.
+#[derive(Clone, Debug, Serialize)]
+struct SearchEntry {
+    name: String,
+    path: String,
+    size: u64,
+    date: DateTime<Utc>,
+    is_dir: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchJson {
+    entries: Vec<SearchEntry>,
+}
+
 
 fn main() -> color_eyre::Result<()> {
-    // 1. write out vendor dir
-    remove_dir_all(VENDOR_DIR_NAME)?;
-    create_dir(VENDOR_DIR_NAME)?;
-    VENDOR_DIR.extract(VENDOR_DIR_NAME)?;
+    let args = Args::parse();
+    let output_root = args.output_dir.as_deref()
+        .map(Path::new)
+        .unwrap_or_else(|| Path::new(&args.root));
+
+    let config = Config {
+        thumbnail_cache_dir: output_root.join(THUMBNAIL_CACHE_DIR_NAME),
+        vendor_dir_name: args.vendor_dir,
+        compute_integrity: args.integrity,
+    };
+
+    // 1. write out vendor dir, tolerating a target that doesn't exist yet
+    let vendor_path = output_root.join(&config.vendor_dir_name);
+    if vendor_path.exists() {
+        remove_dir_all(&vendor_path)?;
+    }
+    create_dir_all(&vendor_path)?;
+    VENDOR_DIR.extract(&vendor_path)?;
+
+    // 2. walk the root tree and generate index.html and index.json
+    //    files to make navigating the cdn easier, skipping directories
+    //    whose contents haven't changed since the last run
+    let mut manifest = load_manifest(output_root);
+    let search_entries = generate_index(Path::new(&args.root), output_root, &mut manifest, &config)?;
+    save_manifest(output_root, &manifest)?;
+
+    // 3. write a whole-tree search index so large cdns can be searched
+    //    instead of clicked through
+    generate_search_index(output_root, &vendor_path, search_entries)
+}
+
+/// Writes the aggregate `search.json` (and its bundled `search.js`
+/// helper) used by `index.html` to offer an instant client-side filter
+/// across the whole tree.
+fn generate_search_index(output_root: &Path, vendor_path: &Path, entries: Vec<SearchEntry>) -> color_eyre::Result<()> {
+    let search_json = output_root.join("search.json");
+    let search_json = {
+        if search_json.exists() {
+            remove_file(&search_json)?;
+        }
 
-    // 2. walk the cwd tree and generate index.html and index.json
-    //    files to make navigating the cdn easier
-    generate_index(".")
+        File::create(search_json)?
+    };
+
+    serde_json::to_writer_pretty(search_json, &SearchJson { entries })?;
+
+    std::fs::write(vendor_path.join("search.js"), SEARCH_JS)?;
+
+    Ok(())
+}
+
+fn load_manifest<P: AsRef<Path>>(path: P) -> HashMap<String, String> {
+    match std::fs::read_to_string(path.as_ref().join(MANIFEST_FILE_NAME)) {
+        Ok(contents) => contents.lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(path, hash)| (path.to_string(), hash.to_string()))
+            .collect(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+fn save_manifest<P: AsRef<Path>>(path: P, manifest: &HashMap<String, String>) -> color_eyre::Result<()> {
+    let mut entries = manifest.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    let contents = entries.into_iter()
+        .map(|(path, hash)| format!("{path}\t{hash}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    std::fs::write(path.as_ref().join(MANIFEST_FILE_NAME), contents)?;
+
+    Ok(())
+}
+
+/// Fingerprints a directory's immediate, non-ignored children by feeding
+/// each one's name, byte size, and modification time into a Blake2b
+/// hasher in a stable (sorted) order. `compute_integrity` is folded in
+/// too, so toggling `--integrity` invalidates every cached entry instead
+/// of leaving stale (integrity-less, or stale-integrity) indices in
+/// place for directories that otherwise look unchanged.
+fn fingerprint(entries: &[(String, u64, DateTime<Utc>)], compute_integrity: bool) -> String {
+    let mut entries = entries.to_vec();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut hasher = Blake2b512::new();
+    hasher.update([compute_integrity as u8]);
+
+    for (name, size, modified) in &entries {
+        hasher.update(name.as_bytes());
+        hasher.update(size.to_le_bytes());
+        hasher.update(modified.timestamp_nanos_opt().unwrap_or_default().to_le_bytes());
+    }
+
+    base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
 }
 
 impl Entry {
+    /// Builds an `Entry` from cheap, always-needed metadata. Thumbnails
+    /// and integrity hashes are deliberately *not* computed here — see
+    /// `fill_in_thumbnails`/`fill_in_integrity`, which are only called
+    /// for directories that are actually being rewritten, so unchanged
+    /// trees don't pay for a full-file read and hash on every run.
     fn new<P: AsRef<Path>>(path: P) -> color_eyre::Result<Self> {
         let path = path.as_ref();
         let metadata = path.metadata()?;
@@ -63,48 +238,200 @@ impl Entry {
             false => format!("{}", Size::from_bytes(metadata.len())),
         };
         let date = DateTime::from(metadata.modified()?);
+        let icon = icon(&path).to_string();
 
         Ok(Self {
             name: path.file_name()
                 .map(|os_str| os_str.to_string_lossy())
                 .unwrap_or(Cow::Borrowed("")).to_string(),
             path: path.to_web_path(),
-            icon: icon(&path).to_string(),
+            icon,
+            thumbnail: None,
             date,
             size,
+            integrity: None,
         })
     }
 }
 
-fn generate_index<P: AsRef<Path>>(path: P) -> color_eyre::Result<()> {
-    eprintln!("Generating indicies for {:?}", path.as_ref());
+/// Fills in `Entry::thumbnail` for a directory's still-media files,
+/// given the filesystem paths they were built from (in the same order).
+/// Only worth calling once a directory's fingerprint has been found
+/// dirty — otherwise this would re-read and re-hash every image on
+/// every run.
+fn fill_in_thumbnails(files: &mut [Entry], file_paths: &[PathBuf], cache_dir: &Path) -> color_eyre::Result<()> {
+    for (entry, path) in files.iter_mut().zip(file_paths) {
+        if entry.icon == "image.png" {
+            entry.thumbnail = thumbnail_for(path, cache_dir)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills in `Entry::integrity` for a directory's files, given the
+/// filesystem paths they were built from (in the same order). Only
+/// worth calling once a directory's fingerprint has been found dirty —
+/// otherwise this would re-read and re-hash every file on every run.
+fn fill_in_integrity(files: &mut [Entry], file_paths: &[PathBuf]) -> color_eyre::Result<()> {
+    for (entry, path) in files.iter_mut().zip(file_paths) {
+        entry.integrity = Some(integrity_for(path)?);
+    }
+
+    Ok(())
+}
+
+/// Computes a Subresource Integrity value (`sha384-<base64 digest>`) that
+/// downstream HTML authors can drop straight into an `integrity="..."`
+/// attribute on a `<script>`/`<link>` tag pointing at this file.
+fn integrity_for(path: &Path) -> color_eyre::Result<String> {
+    let bytes = std::fs::read(path)?;
+
+    let mut hasher = Sha384::new();
+    hasher.update(&bytes);
+
+    Ok(format!("sha384-{}", base64::engine::general_purpose::STANDARD.encode(hasher.finalize())))
+}
+
+/// Generates (or reuses a cached) downscaled thumbnail for a still-media
+/// file, keyed by a content hash so unchanged files aren't re-scaled on
+/// every run. Returns the thumbnail's web path, or `None` if the image
+/// couldn't be decoded.
+///
+/// `cache_dir` is where the thumbnail actually lives on disk (rooted at
+/// `--output-dir`), but the returned web path is root-relative using
+/// just `THUMBNAIL_CACHE_DIR_NAME`, the same basis every other `Entry`
+/// field's path is computed from, rather than `cache_dir`'s own (output
+/// root relative) prefix.
+fn thumbnail_for(path: &Path, cache_dir: &Path) -> color_eyre::Result<Option<String>> {
+    let bytes = std::fs::read(path)?;
+
+    let mut hasher = Blake2b512::new();
+    hasher.update(&bytes);
+    let hash = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(hasher.finalize());
+    let file_name = format!("{hash}.png");
+
+    let thumbnail_path = cache_dir.join(&file_name);
+
+    if !thumbnail_path.exists() {
+        let Ok(image) = image::open(path) else {
+            return Ok(None);
+        };
+
+        create_dir_all(cache_dir)?;
+        image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).save(&thumbnail_path)?;
+    }
+
+    Ok(Some(Path::new(THUMBNAIL_CACHE_DIR_NAME).join(file_name).to_web_path()))
+}
+
+/// Renders a directory's `README.md`/`index.md` to HTML so it can be
+/// shown above the file listing, the way a code-forge directory view
+/// shows a rendered readme. The tree being indexed may come from a
+/// less-trusted uploader, so the rendered HTML is sanitized before it's
+/// handed to the template to rule out a stored-XSS vector via raw
+/// inline HTML/`<script>`/event-handler attributes in the markdown.
+///
+/// Returns `None` rather than an error if the file can't be read, since
+/// `generate_index` is recursive and a single unreadable readme
+/// shouldn't abort the entire walk and lose every other directory's
+/// progress. Non-UTF-8 content is read lossily rather than rejected, the
+/// same tradeoff `thumbnail_for` makes for undecodable images.
+fn render_markdown(path: &Path) -> Option<String> {
+    let bytes = std::fs::read(path).ok()?;
+    let markdown = String::from_utf8_lossy(&bytes);
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(&markdown));
+
+    Some(ammonia::clean(&html))
+}
+
+fn generate_index(input_dir: &Path, output_dir: &Path, manifest: &mut HashMap<String, String>, config: &Config) -> color_eyre::Result<Vec<SearchEntry>> {
     let mut directories = vec![];
     let mut files = vec![];
+    let mut file_paths = vec![];
+    let mut fingerprint_entries = vec![];
+    let mut search_entries = vec![];
+    let mut readme_path = None;
 
-    for dir_entry in path.as_ref().read_dir()? {
+    for dir_entry in input_dir.read_dir()? {
         if let Ok(dir_entry) = dir_entry {
             let entry_path = dir_entry.path();
 
-            if ignore(&entry_path) {
+            if ignore(&entry_path, &config.vendor_dir_name) {
                 continue;
             }
 
-            let entry = Entry::new(entry_path.clone())?;
+            let is_dir = dir_entry.file_type()?.is_dir();
+            let mut child_search_entries = vec![];
+
+            if is_dir {
+                // Recurse first, so that a deletion inside this child
+                // directory invalidates *its own* fingerprint before we
+                // go on to stat it below. We deliberately stat the
+                // directory only after writing into it: index.json/
+                // index.html/feed.xml are written into this very
+                // directory a few lines down, which bumps its own
+                // mtime on most filesystems, and the parent's fingerprint
+                // includes each child's mtime as one of its inputs. If we
+                // captured that mtime before recursing, the parent would
+                // see it change on the very next run (from this run's
+                // writes) and regenerate needlessly — one wasted
+                // cascading rebuild per tree level before the manifest
+                // caught up. Statting after recursion means the mtime we
+                // record already reflects this run's writes, so nothing
+                // looks dirty next time unless something genuinely changed.
+                child_search_entries = generate_index(&entry_path, &output_dir.join(dir_entry.file_name()), manifest, config)?;
+            }
 
-            if dir_entry.file_type()?.is_dir() {
-                generate_index(&entry_path.as_path())?;
+            let entry = Entry::new(entry_path.clone())?;
+            let metadata = entry_path.metadata()?;
+            fingerprint_entries.push((entry.name.clone(), metadata.len(), entry.date));
+
+            search_entries.push(SearchEntry {
+                name: entry.name.clone(),
+                path: entry.path.clone(),
+                size: metadata.len(),
+                date: entry.date,
+                is_dir,
+            });
 
+            if is_dir {
+                search_entries.extend(child_search_entries);
                 directories.push(entry);
+            } else if entry.name == "README.md" || entry.name == "index.md" {
+                // rendered above the listing instead of appearing in it
+                readme_path = Some(entry_path);
             } else {
+                file_paths.push(entry_path);
                 files.push(entry);
             }
         }
     }
 
+    let web_path = input_dir.to_web_path();
+    let hash = fingerprint(&fingerprint_entries, config.compute_integrity);
+
+    if manifest.get(&web_path).is_some_and(|previous| previous == &hash) {
+        eprintln!("Skipping unchanged indicies for {input_dir:?}");
+        return Ok(search_entries);
+    }
+
+    eprintln!("Generating indicies for {input_dir:?}");
+
+    fill_in_thumbnails(&mut files, &file_paths, &config.thumbnail_cache_dir)?;
+
+    if config.compute_integrity {
+        fill_in_integrity(&mut files, &file_paths)?;
+    }
+
     directories.sort_by(|a, b| a.name.cmp(&b.name));
     files.sort_by(|a, b| b.date.cmp(&a.date));
 
-    let index_json = path.as_ref().join("index.json");
+    create_dir_all(output_dir)?;
+
+    let index_json = output_dir.join("index.json");
     let index_json = {
         if index_json.exists() {
             remove_file(&index_json)?;
@@ -117,9 +444,11 @@ fn generate_index<P: AsRef<Path>>(path: P) -> color_eyre::Result<()> {
         entries: files.clone()
     })?;
 
+    generate_feed(output_dir, &web_path, &files)?;
+
     let entries = directories.into_iter().chain(files.into_iter()).collect::<Vec<_>>();
 
-    let index_html = path.as_ref().join("index.html");
+    let index_html = output_dir.join("index.html");
     let mut index_html = {
         if index_html.exists() {
             remove_file(&index_html)?;
@@ -128,21 +457,66 @@ fn generate_index<P: AsRef<Path>>(path: P) -> color_eyre::Result<()> {
         File::create(index_html)?
     };
 
-    let breadcrumbs = path.as_ref().to_breadcrumbs();
+    let breadcrumbs = input_dir.to_breadcrumbs();
+    let readme_html = readme_path.and_then(|path| render_markdown(&path));
 
     IndexHtml {
-        vendor_dir: VENDOR_DIR_NAME.to_string(),
+        vendor_dir: config.vendor_dir_name.clone(),
         breadcrumbs,
         entries,
+        readme_html,
     }.write_into(&mut index_html)?;
 
+    manifest.insert(web_path, hash);
+
+    Ok(search_entries)
+}
+
+/// Writes `feed.xml` for a directory so it can be subscribed to with an
+/// RSS/Atom reader; items are the `FEED_ITEM_LIMIT` newest files, relying
+/// on `files` already being sorted by descending `date`.
+fn generate_feed(output_dir: &Path, web_path: &str, files: &[Entry]) -> color_eyre::Result<()> {
+    let items = files.iter()
+        .take(FEED_ITEM_LIMIT)
+        .map(|entry| ItemBuilder::default()
+            .title(Some(entry.name.clone()))
+            .link(Some(entry.path.clone()))
+            .guid(Some(Guid {
+                value: entry.path.clone(),
+                permalink: false,
+            }))
+            .pub_date(Some(entry.date.to_rfc2822()))
+            .build())
+        .collect::<Vec<_>>();
+
+    let channel = ChannelBuilder::default()
+        .title(format!("cdnizer: {web_path}"))
+        .link(web_path.to_string())
+        .description("Recently modified files".to_string())
+        .items(items)
+        .build();
+
+    let feed_xml = output_dir.join("feed.xml");
+    let feed_xml = {
+        if feed_xml.exists() {
+            remove_file(&feed_xml)?;
+        }
+
+        File::create(feed_xml)?
+    };
+
+    channel.write_to(feed_xml)?;
+
     Ok(())
 }
 
-fn ignore(path: &Path) -> bool {
-    path.file_name().map(|os_str| os_str.to_string_lossy() == VENDOR_DIR_NAME).unwrap_or(false) ||
+fn ignore(path: &Path, vendor_dir_name: &str) -> bool {
+    path.file_name().map(|os_str| (
+        os_str == vendor_dir_name || os_str == THUMBNAIL_CACHE_DIR_NAME
+    )).unwrap_or(false) ||
         path.file_name().map(|fname| (
-            fname == "index.html" || fname == "index.json"
+            fname == "index.html" || fname == "index.json" || fname == "feed.xml" ||
+                fname == "search.json" || fname == MANIFEST_FILE_NAME
         )).unwrap_or(false)
 }
 
@@ -225,3 +599,67 @@ impl ToBreadcrumbs for Path {
         crumbs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_is_order_independent() {
+        let now = Utc::now();
+        let a = vec![
+            ("a.txt".to_string(), 10, now),
+            ("b.txt".to_string(), 20, now),
+        ];
+        let b = vec![
+            ("b.txt".to_string(), 20, now),
+            ("a.txt".to_string(), 10, now),
+        ];
+
+        assert_eq!(fingerprint(&a, false), fingerprint(&b, false));
+    }
+
+    #[test]
+    fn fingerprint_changes_with_compute_integrity() {
+        let entries = vec![("a.txt".to_string(), 10, Utc::now())];
+
+        assert_ne!(fingerprint(&entries, false), fingerprint(&entries, true));
+    }
+
+    /// Regression test for the cascading-rebuild bug fixed in a9a6467:
+    /// a directory's own mtime must be captured *after* recursing into
+    /// it, since generate_index writes index.html/index.json/feed.xml
+    /// into every directory it regenerates. Capturing the mtime beforehand
+    /// meant a parent saw its child "change" on the very next run (from
+    /// that run's own writes) and regenerated needlessly.
+    #[test]
+    fn generate_index_converges_after_one_run() -> color_eyre::Result<()> {
+        let root = std::env::temp_dir().join(format!("cdnizer-test-{}", std::process::id()));
+        if root.exists() {
+            remove_dir_all(&root)?;
+        }
+        create_dir_all(root.join("child"))?;
+        std::fs::write(root.join("child").join("file.txt"), "hello")?;
+
+        let config = Config {
+            vendor_dir_name: VENDOR_DIR_NAME.to_string(),
+            compute_integrity: false,
+            thumbnail_cache_dir: root.join(THUMBNAIL_CACHE_DIR_NAME),
+        };
+
+        let mut manifest = HashMap::new();
+        generate_index(&root, &root, &mut manifest, &config)?;
+        let first_run_manifest = manifest.clone();
+
+        // A second run against the tree as the first run left it
+        // (including the index files it just wrote) must see the same
+        // fingerprints, not mistake its own output for a fresh change.
+        generate_index(&root, &root, &mut manifest, &config)?;
+
+        assert_eq!(first_run_manifest, manifest);
+
+        remove_dir_all(&root)?;
+
+        Ok(())
+    }
+}